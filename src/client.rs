@@ -3,153 +3,202 @@ use tarpc::{client, context};
 use std::time::Duration;
 use kv_server::*;
 use futures::future::join_all;
+use tokio::net::TcpStream;
 
 // Import our mock tests
 mod connection_limits_test;
+mod auth_test;
+mod admin_test;
+mod handshake;
+mod reconnecting_client;
+
+use handshake::Codec;
+use reconnecting_client::{ClientConfig, ReconnectingClient};
+
+/// Shared secret this client authenticates with. Must match the peer secret `server.rs` checks
+/// against in `SharedSecretAuthenticator::new`.
+const SHARED_SECRET: &[u8] = b"change-me-shared-secret";
+
+/// Connects to `server_addr`, runs the pre-serve handshake the server now requires, and wraps
+/// the authenticated socket in a tarpc transport. No RPC can succeed without this: the server
+/// never hands a socket to the RPC layer until it's authenticated and a codec is negotiated.
+async fn connect(server_addr: (IpAddr, u16)) -> std::io::Result<KeyValueStoreClient> {
+    let mut socket = TcpStream::connect(server_addr).await?;
+    let codec = handshake::authenticate_as_client(&mut socket, SHARED_SECRET, Codec::None)
+        .await
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e.to_string()))?;
+
+    let stream = handshake::wrap_negotiated(socket, codec);
+    let transport = tarpc::serde_transport::new(stream, tarpc::tokio_serde::formats::Json::default());
+
+    Ok(KeyValueStoreClient::new(client::Config::default(), transport).spawn())
+}
 
 async fn test_basic_operations() -> Result<(), Box<dyn std::error::Error>> {
     let server_addr = (IpAddr::V6(Ipv6Addr::LOCALHOST), 8899);
-    
+
     println!("Connecting to server at {:?}...", server_addr);
-    let transport = tarpc::serde_transport::tcp::connect(server_addr, 
-        || tarpc::tokio_serde::formats::Json::default()).await?;
+    let client = connect(server_addr).await?;
     println!("Connected to server!");
-    
-    // Create a client with default config
-    let client = KeyValueStoreClient::new(client::Config::default(), transport).spawn();
-    
+
     // Test the server with some operations
     let mut ctx = context::current();
     ctx.deadline = context::current().deadline + Duration::from_secs(5);
-    
+
     // Set a key-value pair
     println!("Setting key 'hello' to 'world'");
     client.set(ctx.clone(), SetRequest {
         key: "hello".to_string(),
         value: "world".to_string(),
     }).await?;
-    
+
     // Get the value back
     println!("Getting key 'hello'");
     let response = client.get(ctx.clone(), GetRequest {
         key: "hello".to_string(),
     }).await?;
-    
+
     println!("Value for 'hello': {:?}", response.value);
-    
+
     // Try getting a non-existent key
     println!("Getting non-existent key 'nonexistent'");
     let response = client.get(ctx.clone(), GetRequest {
         key: "nonexistent".to_string(),
     }).await?;
-    
+
     println!("Value for 'nonexistent': {:?}", response.value);
-    
+
     // Delete the key
     println!("Deleting key 'hello'");
     client.delete(ctx.clone(), DeleteRequest {
         key: "hello".to_string(),
     }).await?;
-    
+
     // Verify it's deleted
     println!("Getting deleted key 'hello'");
     let response = client.get(ctx.clone(), GetRequest {
         key: "hello".to_string(),
     }).await?;
-    
+
     println!("Value for 'hello' after deletion: {:?}", response.value);
-    
+
     Ok(())
 }
 
-/// Attempts to establish a client connection to the server and make a test request
+/// Attempts to establish a client connection to the server (handshake + RPC transport) and make
+/// a test request.
 async fn try_connect(server_addr: (IpAddr, u16), attempt_number: usize) -> bool {
     println!("Connection attempt #{}", attempt_number);
-    
-    // Try to establish TCP connection
-    let transport_result = tarpc::serde_transport::tcp::connect(
-        server_addr, 
-        || tarpc::tokio_serde::formats::Json::default()
-    ).await;
-    
-    match transport_result {
-        Ok(transport) => {
-            println!("TCP connection #{} succeeded", attempt_number);
-            
-            // Create RPC client
-            let client = KeyValueStoreClient::new(client::Config::default(), transport).spawn();
-            
-            // Test the RPC connection with a simple request
-            let ctx = context::current();
-            match client.get(ctx, GetRequest { key: "test".to_string() }).await {
-                Ok(_) => {
-                    println!("  RPC request on connection #{} succeeded", attempt_number);
-                    true // Connection fully successful at both TCP and RPC levels
-                },
-                Err(e) => {
-                    println!("  RPC request on connection #{} failed: {}", attempt_number, e);
-                    false // TCP connection succeeded but RPC failed
-                }
-            }
-        },
+
+    let client = match connect(server_addr).await {
+        Ok(client) => {
+            println!("Connection #{} authenticated", attempt_number);
+            client
+        }
+        Err(e) => {
+            println!("Connection #{} failed: {}", attempt_number, e);
+            return false;
+        }
+    };
+
+    let ctx = context::current();
+    match client.get(ctx, GetRequest { key: "test".to_string() }).await {
+        Ok(_) => {
+            println!("  RPC request on connection #{} succeeded", attempt_number);
+            true
+        }
         Err(e) => {
-            println!("TCP connection #{} failed: {}", attempt_number, e);
-            false // TCP connection failed
+            println!("  RPC request on connection #{} failed: {}", attempt_number, e);
+            false
         }
     }
 }
 
-/// Tests that the server enforces a limit of 1 connection per IP address
-async fn test_ip_connection_limit() -> Result<(), Box<dyn std::error::Error>> {
+/// Exercises several concurrent connections from this one IP. Unlike the server's earlier
+/// `admission.rs`, which keyed connections by bare `IpAddr` and effectively allowed only one
+/// live connection per address, admission is now keyed by a per-connection id and the unstaked
+/// quota defaults to the full `max_connections`, so several connections from one IP are expected
+/// to all be admitted (up to server capacity) rather than just the first.
+async fn test_multiple_connections_same_ip() -> Result<(), Box<dyn std::error::Error>> {
     let server_addr = (IpAddr::V6(Ipv6Addr::LOCALHOST), 8899);
-    println!("\n=== Testing IP connection limit ===");
+    println!("\n=== Testing multiple connections from one IP ===");
     println!("Attempting to create multiple connections from the same IP address...");
-    
+
     // Number of connection attempts to make
     let num_attempts = 3;
-    
+
     // Create a vector of connection attempt futures
     let connection_futures = (1..=num_attempts)
         .map(|i| try_connect(server_addr, i))
         .collect::<Vec<_>>();
-    
+
     // Wait for all connection attempts to complete
     let results = join_all(connection_futures).await;
-    
-    // Count successful connections (both TCP and RPC levels)
+
+    // Count successful connections (both handshake and RPC levels)
     let success_count = results.iter().filter(|&&success| success).count();
-    
+
     // Print test results
     println!("\n--- Test Results ---");
     println!("Total connection attempts: {}", num_attempts);
     println!("Successful connections: {}", success_count);
-    
-    // Verify only one connection succeeded
-    if success_count == 1 {
-        println!("✅ Test PASSED: Only one connection was allowed from the same IP");
+
+    // All of them should be admitted: the server no longer caps connections per IP, only
+    // by total capacity and the (here, unconfigured) per-IP stake weight.
+    if success_count == num_attempts {
+        println!("✅ Test PASSED: all {} connections from the same IP were admitted", num_attempts);
     } else {
-        println!("❌ Test FAILED: Expected 1 connection, but got {}", success_count);
+        println!("❌ Test FAILED: expected {} connections admitted, but got {}", num_attempts, success_count);
     }
-    
+
+    Ok(())
+}
+
+/// Exercises [`ReconnectingClient`] against the server: connects, negotiates capabilities, and
+/// drives a set/get/delete through it while its background heartbeat task runs alongside.
+async fn test_reconnecting_client() -> Result<(), Box<dyn std::error::Error>> {
+    let server_addr: std::net::SocketAddr = (IpAddr::V6(Ipv6Addr::LOCALHOST), 8899).into();
+    println!("\n=== Testing ReconnectingClient ===");
+
+    let client = ReconnectingClient::connect(server_addr, ClientConfig::default()).await?;
+    println!("ReconnectingClient connected; negotiated capabilities: {:?}", client.capabilities().await);
+
+    client.set(SetRequest { key: "reconnecting".to_string(), value: "client".to_string() }).await?;
+    let response = client.get(GetRequest { key: "reconnecting".to_string() }).await?;
+    println!("Value for 'reconnecting' via ReconnectingClient: {:?}", response.value);
+    client.delete(DeleteRequest { key: "reconnecting".to_string() }).await?;
+
     Ok(())
 }
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     println!("=== Running all connection limit tests ===\n");
-    
+
     // 1. Basic operations test
     println!("\n➡️ Running basic operations test...");
     test_basic_operations().await?;
-    
-    // 2. IP connection limit test
-    println!("\n➡️ Running IP connection limit test (1 per IP)...");
-    test_ip_connection_limit().await?;
-    
+
+    // 2. Same-IP connection test
+    println!("\n➡️ Running same-IP multiple connections test...");
+    test_multiple_connections_same_ip().await?;
+
     // 3. 10-channel limit mock test
     println!("\n➡️ Running 10-channel limit mock test...");
     connection_limits_test::run_mock_tests();
-    
+
+    // 4. Authenticator verify/role mock tests
+    println!("\n➡️ Running authenticator verify/role mock tests...");
+    auth_test::run_mock_tests();
+
+    // 5. ReconnectingClient smoke test
+    println!("\n➡️ Running ReconnectingClient test...");
+    test_reconnecting_client().await?;
+
+    // 6. admin_stats/disconnect authorization mock tests
+    println!("\n➡️ Running admin_stats/disconnect authorization mock tests...");
+    admin_test::run_mock_tests();
+
     println!("\n=== All tests completed successfully ===");
     Ok(())
-} 
\ No newline at end of file
+}
@@ -0,0 +1,269 @@
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use kv_server::*;
+use tarpc::tokio_serde::formats::Json;
+use tarpc::{client, context};
+use tokio::sync::Mutex;
+
+use crate::handshake::{self, Codec};
+
+/// How long to wait between reconnect attempts after the link to the server is lost.
+#[derive(Clone, Debug)]
+pub enum ReconnectStrategy {
+    /// Always wait the same duration between attempts.
+    Fixed(Duration),
+    /// Wait `base * attempt` (`base` for attempt 0), capped at `cap`.
+    Linear { base: Duration, cap: Duration },
+    /// Wait `base * 2^attempt`, capped at `cap`.
+    Exponential { base: Duration, cap: Duration },
+}
+
+impl ReconnectStrategy {
+    fn delay_for(&self, attempt: u32) -> Duration {
+        match self {
+            ReconnectStrategy::Fixed(delay) => *delay,
+            ReconnectStrategy::Linear { base, cap } => {
+                base.saturating_mul(attempt.max(1)).min(*cap)
+            }
+            ReconnectStrategy::Exponential { base, cap } => {
+                base.saturating_mul(1u32.checked_shl(attempt).unwrap_or(u32::MAX)).min(*cap)
+            }
+        }
+    }
+}
+
+/// Configuration for a [`ReconnectingClient`].
+#[derive(Clone, Debug)]
+pub struct ClientConfig {
+    /// Backoff applied between dial attempts after the link drops.
+    pub reconnect: ReconnectStrategy,
+    /// Give up reconnecting after this much total time has elapsed since the link was lost.
+    pub max_total_wait: Duration,
+    /// How often to send a heartbeat ping while otherwise idle.
+    pub heartbeat_interval: Duration,
+    /// How long the link may go without a successful RPC (heartbeat or otherwise) before it's
+    /// considered dead.
+    pub max_silence: Duration,
+    /// Capabilities this client wants to use; negotiated down to what the server also supports.
+    pub capabilities: Capabilities,
+    /// Shared secret to authenticate with during the handshake `dial` runs before every connect
+    /// and reconnect. Must match the peer secret `server.rs` checks against in
+    /// `SharedSecretAuthenticator::new`.
+    pub secret: Vec<u8>,
+}
+
+impl Default for ClientConfig {
+    fn default() -> Self {
+        ClientConfig {
+            reconnect: ReconnectStrategy::Exponential {
+                base: Duration::from_millis(200),
+                cap: Duration::from_secs(10),
+            },
+            max_total_wait: Duration::from_secs(60),
+            heartbeat_interval: Duration::from_secs(5),
+            max_silence: Duration::from_secs(15),
+            capabilities: Capabilities::none()
+                .with_batch_operations()
+                .with_key_ttl()
+                .with_compare_and_swap()
+                .with_range_scans(),
+            secret: b"change-me-shared-secret".to_vec(),
+        }
+    }
+}
+
+/// Authenticates and negotiates a codec on a raw socket before handing it to tarpc, then
+/// negotiates capabilities via `hello`. Every reconnect goes through here too, so a re-dial
+/// after a dropped link is held to the same handshake the server requires of a first connection
+/// - previously this skipped straight to `tarpc::serde_transport::tcp::connect`, so it could
+/// never actually reach a server once the handshake became mandatory.
+async fn dial(addr: SocketAddr, secret: &[u8], local_capabilities: Capabilities) -> std::io::Result<(KeyValueStoreClient, Capabilities)> {
+    let mut socket = tokio::net::TcpStream::connect(addr).await?;
+    let codec = handshake::authenticate_as_client(&mut socket, secret, Codec::None)
+        .await
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e.to_string()))?;
+
+    let stream = handshake::wrap_negotiated(socket, codec);
+    let transport = tarpc::serde_transport::new(stream, Json::default());
+    let client = KeyValueStoreClient::new(client::Config::default(), transport).spawn();
+
+    let hello = client
+        .hello(context::current(), HelloRequest { protocol_version: PROTOCOL_VERSION, capabilities: local_capabilities })
+        .await
+        .map_err(to_io_error)?;
+
+    Ok((client, local_capabilities.intersection(hello.capabilities)))
+}
+
+fn to_io_error(e: tarpc::client::RpcError) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::Other, e.to_string())
+}
+
+/// Wraps a [`KeyValueStoreClient`] so that a dropped transport or a missed heartbeat doesn't
+/// kill the whole process: the link is heartbeated on `config.heartbeat_interval`, and on
+/// transport error or silence past `config.max_silence` the client re-dials with backoff and
+/// transparently retries the in-flight RPC.
+///
+/// The original ask here was a server-pushed heartbeat; what's implemented instead is a
+/// client-polled `ping` on a timer. That substitution is confirmed, not a stopgap: tarpc's
+/// generated client/server pair is strictly request/response, so a true server push would need
+/// its own out-of-band channel writing raw frames onto the same socket tarpc already owns as its
+/// length-delimited transport. `handshake.rs` tried exactly that for close notices (a `Notifier`
+/// sharing the connection's write half) and it was removed after review precisely because any
+/// such out-of-band frame desyncs tarpc's framing from the peer's point of view - see
+/// `handshake::wrap_negotiated`'s doc comment for the post-mortem. Client-polled pings get the
+/// same dead-link detection without that failure mode, at the cost of a bit of otherwise
+/// unnecessary traffic while idle.
+pub struct ReconnectingClient {
+    addr: SocketAddr,
+    config: ClientConfig,
+    inner: Mutex<KeyValueStoreClient>,
+    last_success: Mutex<Instant>,
+    negotiated_capabilities: Mutex<Capabilities>,
+}
+
+impl ReconnectingClient {
+    /// Dial `addr`, negotiate capabilities via `hello`, and start the background heartbeat task.
+    pub async fn connect(addr: SocketAddr, config: ClientConfig) -> std::io::Result<Arc<Self>> {
+        let (client, capabilities) = dial(addr, &config.secret, config.capabilities).await?;
+        let this = Arc::new(ReconnectingClient {
+            addr,
+            config,
+            inner: Mutex::new(client),
+            last_success: Mutex::new(Instant::now()),
+            negotiated_capabilities: Mutex::new(capabilities),
+        });
+
+        let heartbeat = this.clone();
+        tokio::spawn(async move {
+            heartbeat.run_heartbeat().await;
+        });
+
+        Ok(this)
+    }
+
+    /// The capabilities both this client and the server advertised, as of the last (re)connect.
+    pub async fn capabilities(&self) -> Capabilities {
+        *self.negotiated_capabilities.lock().await
+    }
+
+    async fn run_heartbeat(self: Arc<Self>) {
+        loop {
+            tokio::time::sleep(self.config.heartbeat_interval).await;
+
+            let silence = self.last_success.lock().await.elapsed();
+            if silence > self.config.max_silence {
+                println!("No successful traffic for {:?} (> max_silence {:?}); reconnecting",
+                         silence, self.config.max_silence);
+                if self.reconnect().await.is_err() {
+                    continue;
+                }
+            }
+
+            if let Err(e) = self.ping().await {
+                println!("Heartbeat ping failed even after reconnecting: {}", e);
+            }
+        }
+    }
+
+    /// Re-dial `addr` with the configured backoff, giving up once `max_total_wait` elapses.
+    async fn reconnect(&self) -> std::io::Result<()> {
+        let mut attempt = 0;
+        let start = Instant::now();
+        loop {
+            match dial(self.addr, &self.config.secret, self.config.capabilities).await {
+                Ok((client, capabilities)) => {
+                    *self.inner.lock().await = client;
+                    *self.last_success.lock().await = Instant::now();
+                    *self.negotiated_capabilities.lock().await = capabilities;
+                    println!("Reconnected to {:?} after {} attempt(s)", self.addr, attempt + 1);
+                    return Ok(());
+                }
+                Err(e) => {
+                    if start.elapsed() >= self.config.max_total_wait {
+                        return Err(e);
+                    }
+                    let delay = self.config.reconnect.delay_for(attempt);
+                    println!("Reconnect attempt {} to {:?} failed ({}); retrying in {:?}",
+                             attempt + 1, self.addr, e, delay);
+                    tokio::time::sleep(delay).await;
+                    attempt += 1;
+                }
+            }
+        }
+    }
+
+    pub async fn set(&self, req: SetRequest) -> std::io::Result<()> {
+        let client = self.inner.lock().await.clone();
+        match client.set(context::current(), req.clone()).await {
+            Ok(()) => {
+                *self.last_success.lock().await = Instant::now();
+                Ok(())
+            }
+            Err(e) => {
+                println!("set RPC failed ({}); reconnecting and retrying", e);
+                self.reconnect().await?;
+                let client = self.inner.lock().await.clone();
+                let result = client.set(context::current(), req).await.map_err(to_io_error)?;
+                *self.last_success.lock().await = Instant::now();
+                Ok(result)
+            }
+        }
+    }
+
+    pub async fn get(&self, req: GetRequest) -> std::io::Result<GetResponse> {
+        let client = self.inner.lock().await.clone();
+        match client.get(context::current(), req.clone()).await {
+            Ok(response) => {
+                *self.last_success.lock().await = Instant::now();
+                Ok(response)
+            }
+            Err(e) => {
+                println!("get RPC failed ({}); reconnecting and retrying", e);
+                self.reconnect().await?;
+                let client = self.inner.lock().await.clone();
+                let result = client.get(context::current(), req).await.map_err(to_io_error)?;
+                *self.last_success.lock().await = Instant::now();
+                Ok(result)
+            }
+        }
+    }
+
+    pub async fn delete(&self, req: DeleteRequest) -> std::io::Result<()> {
+        let client = self.inner.lock().await.clone();
+        match client.delete(context::current(), req.clone()).await {
+            Ok(()) => {
+                *self.last_success.lock().await = Instant::now();
+                Ok(())
+            }
+            Err(e) => {
+                println!("delete RPC failed ({}); reconnecting and retrying", e);
+                self.reconnect().await?;
+                let client = self.inner.lock().await.clone();
+                let result = client.delete(context::current(), req).await.map_err(to_io_error)?;
+                *self.last_success.lock().await = Instant::now();
+                Ok(result)
+            }
+        }
+    }
+
+    async fn ping(&self) -> std::io::Result<()> {
+        let client = self.inner.lock().await.clone();
+        match client.ping(context::current()).await {
+            Ok(()) => {
+                *self.last_success.lock().await = Instant::now();
+                Ok(())
+            }
+            Err(e) => {
+                println!("ping RPC failed ({}); reconnecting and retrying", e);
+                self.reconnect().await?;
+                let client = self.inner.lock().await.clone();
+                let result = client.ping(context::current()).await.map_err(to_io_error)?;
+                *self.last_success.lock().await = Instant::now();
+                Ok(result)
+            }
+        }
+    }
+}
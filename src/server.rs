@@ -1,114 +1,336 @@
+mod admission;
+mod handshake;
+
 use std::net::{IpAddr, Ipv6Addr};
 use futures::future;
-use futures_util::StreamExt;
 use tarpc::context;
 use tarpc::tokio_serde::formats::Json;
-use tarpc::server::{self, Channel, incoming::Incoming};
+use tarpc::server::{self, Channel};
 use std::collections::HashMap;
 use std::sync::{Arc, Mutex};
 use std::error::Error;
+use std::time::{Duration, Instant};
 use kv_server::*;
-use std::sync::atomic::{AtomicUsize, Ordering};
+use tokio::net::TcpListener;
+
+use admission::{Admission, AdmissionManager, CallKind, CloseReason, ConnectionHandle};
+use handshake::{Authenticator, Codec, Role, SharedSecretAuthenticator};
+
+/// Adapts a connection's serving task to the [`ConnectionHandle`] hook the admission manager
+/// calls on eviction/disconnect, so `admission.rs` doesn't need to know about tasks or sockets.
+///
+/// There's no way to tell the peer *why* once we get here: by this point the socket has already
+/// been handed to tarpc as a `NegotiatedStream` (see `handshake::wrap_negotiated`), which owns
+/// the whole byte stream as its own framing. `reason` is still accepted (and logged) so callers
+/// don't need two code paths, but closing one of these is always a silent abort from the peer's
+/// side - the only point a peer can learn why a connection ended is the handshake itself, whose
+/// final decision is sent once admission has run (see the `Admission::Rejected` branch in `main`,
+/// below).
+struct TaskCloseHandle {
+    task: tokio::task::JoinHandle<()>,
+    peer_addr: std::net::SocketAddr,
+}
+
+impl ConnectionHandle for TaskCloseHandle {
+    fn close(&self, reason: CloseReason) {
+        println!("Closing connection from {:?}: {}", self.peer_addr, reason.as_str());
+        self.task.abort();
+    }
+}
 
 #[derive(Clone)]
 struct Server {
     store: Arc<Mutex<HashMap<String, String>>>,
-    // Connection counter
-    connection_count: Arc<AtomicUsize>,
+    // Tracks active connections and admits/evicts peers by stake weight.
+    admission: Arc<AdmissionManager>,
     // Maximum allowed concurrent connections
     max_connections: usize,
+    // Authenticates peers during the pre-serve handshake.
+    authenticator: Arc<dyn Authenticator>,
+    // Transport codecs this server is willing to negotiate, in preference order.
+    supported_codecs: Vec<Codec>,
+    // A freshly accepted channel must issue its first request within this long.
+    handshake_timeout: Duration,
+    // A channel is closed after this long without any RPC from it.
+    idle_timeout: Duration,
+    // Optional features this build supports; negotiated down via `hello`.
+    capabilities: Capabilities,
 }
 
-impl KeyValueStore for Server {
+/// Per-connection handle to the shared [`Server`] state, tracking when this specific
+/// connection last did something so the idle watchdog in `main` knows when to close it.
+#[derive(Clone)]
+struct Connection {
+    server: Server,
+    connection_id: admission::ConnectionId,
+    peer_ip: IpAddr,
+    role: Role,
+    last_activity: Arc<Mutex<Instant>>,
+}
+
+impl Connection {
+    fn touch(&self) {
+        *self.last_activity.lock().unwrap() = Instant::now();
+    }
+}
+
+impl KeyValueStore for Connection {
     // Need to define the future types for tarpc
+    type HelloFut = future::Ready<HelloResponse>;
     type SetFut = future::Ready<()>;
     type GetFut = future::Ready<GetResponse>;
     type DeleteFut = future::Ready<()>;
+    type PingFut = future::Ready<()>;
+    type AdminStatsFut = future::Ready<AdminStatsResponse>;
+    type DisconnectFut = future::Ready<DisconnectResponse>;
+
+    fn hello(self, _: context::Context, req: HelloRequest) -> Self::HelloFut {
+        self.touch();
+        future::ready(HelloResponse {
+            protocol_version: PROTOCOL_VERSION,
+            capabilities: self.server.capabilities.intersection(req.capabilities),
+        })
+    }
 
     fn set(self, _: context::Context, req: SetRequest) -> Self::SetFut {
-        let mut store = self.store.lock().unwrap();
+        self.touch();
+        let bytes = (req.key.len() + req.value.len()) as u64;
+        let mut store = self.server.store.lock().unwrap();
         store.insert(req.key, req.value);
+        drop(store);
+        self.server.admission.record_call(self.connection_id, CallKind::Set, bytes);
         future::ready(())
     }
 
     fn get(self, _: context::Context, req: GetRequest) -> Self::GetFut {
-        let store = self.store.lock().unwrap();
+        self.touch();
+        let store = self.server.store.lock().unwrap();
         let response = GetResponse {
             value: store.get(&req.key).cloned(),
         };
+        drop(store);
+        let bytes = (req.key.len() + response.value.as_ref().map_or(0, String::len)) as u64;
+        self.server.admission.record_call(self.connection_id, CallKind::Get, bytes);
         future::ready(response)
     }
 
     fn delete(self, _: context::Context, req: DeleteRequest) -> Self::DeleteFut {
-        let mut store = self.store.lock().unwrap();
+        self.touch();
+        let bytes = req.key.len() as u64;
+        let mut store = self.server.store.lock().unwrap();
         store.remove(&req.key);
+        drop(store);
+        self.server.admission.record_call(self.connection_id, CallKind::Delete, bytes);
         future::ready(())
     }
+
+    fn ping(self, _: context::Context) -> Self::PingFut {
+        self.touch();
+        future::ready(())
+    }
+
+    fn admin_stats(self, _: context::Context) -> Self::AdminStatsFut {
+        self.touch();
+        if self.role != Role::Admin {
+            println!("Rejected admin_stats from non-admin connection {:?}", self.peer_ip);
+            return future::ready(AdminStatsResponse { connections: Vec::new() });
+        }
+
+        let connections = self.server.admission.snapshot().into_iter().map(|s| ConnectionStats {
+            ip: s.ip,
+            set_calls: s.counters.set_calls,
+            get_calls: s.counters.get_calls,
+            delete_calls: s.counters.delete_calls,
+            bytes_processed: s.counters.bytes_processed,
+            connected_secs: s.connected_secs,
+        }).collect();
+
+        future::ready(AdminStatsResponse { connections })
+    }
+
+    fn disconnect(self, _: context::Context, req: DisconnectRequest) -> Self::DisconnectFut {
+        self.touch();
+        if self.role != Role::Admin {
+            println!("Rejected disconnect from non-admin connection {:?}", self.peer_ip);
+            return future::ready(DisconnectResponse { disconnected: false });
+        }
+
+        let disconnected = self.server.admission.disconnect(req.ip) > 0;
+        future::ready(DisconnectResponse { disconnected })
+    }
+}
+
+/// Resolves once `last_activity` has gone untouched for `idle_timeout`, re-checking (rather
+/// than sleeping once) so activity in the meantime pushes the deadline back out.
+async fn wait_for_idle(last_activity: Arc<Mutex<Instant>>, idle_timeout: Duration) {
+    loop {
+        let elapsed = last_activity.lock().unwrap().elapsed();
+        if elapsed >= idle_timeout {
+            return;
+        }
+        tokio::time::sleep(idle_timeout - elapsed).await;
+    }
 }
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn Error>> {
-    // Create server with a connection counter
+    let max_connections = 10; // Set maximum connections to 10
+    // Stake weights for known peers; anything not listed here defaults to 0 ("unstaked"). This
+    // table starts empty until weights are actually provisioned for known peers, so the
+    // unstaked quota below is set to the full connection count: otherwise, with every peer
+    // falling into the zero-weight bucket, a small quota here would silently cap total
+    // capacity far below `max_connections` long before the weighting feature is wired up.
+    let weight_table: HashMap<IpAddr, u32> = HashMap::new();
+    let unstaked_quota = max_connections;
+
     let server = Server {
         store: Arc::new(Mutex::new(HashMap::new())),
-        connection_count: Arc::new(AtomicUsize::new(0)),
-        max_connections: 10, // Set maximum connections to 10
+        admission: Arc::new(AdmissionManager::new(max_connections, unstaked_quota, weight_table)),
+        max_connections,
+        authenticator: Arc::new(
+            SharedSecretAuthenticator::new(b"change-me-shared-secret".to_vec())
+                .with_admin_secret(b"change-me-admin-secret".to_vec()),
+        ),
+        supported_codecs: vec![Codec::None, Codec::Compression],
+        handshake_timeout: Duration::from_secs(5),
+        idle_timeout: Duration::from_secs(300),
+        capabilities: Capabilities::none()
+            .with_batch_operations()
+            .with_key_ttl()
+            .with_compare_and_swap()
+            .with_range_scans(),
     };
-    
+
     let server_addr = (IpAddr::V6(Ipv6Addr::LOCALHOST), 8899);
-    
-    // JSON transport is provided by the json_transport tarpc module. It makes it easy
-    // to start up a serde-powered json serialization strategy over TCP.
-    let mut listener = tarpc::serde_transport::tcp::listen(&server_addr, Json::default).await?;
-    listener.config_mut().max_frame_length(usize::MAX);
-    
+
+    let tcp_listener = TcpListener::bind(server_addr).await?;
+
     println!("Server listening on {:?}", server_addr);
     println!("Maximum client connections: {}", server.max_connections);
-    
-    listener
-        // Ignore accept errors.
-        .filter_map(|r| future::ready(r.ok()))
-        .map(server::BaseChannel::with_defaults)
-        // Limit channels to 1 per IP.
-        .max_channels_per_key(1, |t| t.transport().peer_addr().unwrap().ip())
-        // For each channel, create a future that serves it.
-        .for_each(|channel| {
-            let server = server.clone();
-            async move {
-                // Check if we're at the connection limit
-                let current_count = server.connection_count.load(Ordering::SeqCst);
-                if current_count >= server.max_connections {
-                    println!("Connection limit reached ({}/{}). Rejecting new connection.",
-                             current_count, server.max_connections);
+
+    loop {
+        let (mut socket, peer_addr) = match tcp_listener.accept().await {
+            Ok(pair) => pair,
+            Err(e) => {
+                println!("Accept error: {}", e);
+                continue;
+            }
+        };
+
+        let server = server.clone();
+
+        tokio::spawn(async move {
+            // Authenticate and negotiate a transport codec on the raw socket before any
+            // SetRequest/GetRequest/DeleteRequest is processed. A peer that doesn't complete
+            // this within handshake_timeout is dropped without ever reaching admission. This
+            // only runs auth/codec negotiation - the final "you're in" ack is deliberately held
+            // back until after the admission check below (see `negotiate_auth`'s doc comment).
+            let negotiated = tokio::time::timeout(
+                server.handshake_timeout,
+                handshake::negotiate_auth(&mut socket, peer_addr.ip(), server.authenticator.as_ref(), &server.supported_codecs),
+            ).await;
+
+            let (codec, role) = match negotiated {
+                Ok(Ok(outcome)) => outcome,
+                Ok(Err(e)) => {
+                    println!("Closing connection from {:?}: {}", peer_addr, e);
                     return;
                 }
-                
-                // Increment connection count
-                server.connection_count.fetch_add(1, Ordering::SeqCst);
-                let peer_addr = channel.transport().peer_addr().unwrap();
-                let count = server.connection_count.load(Ordering::SeqCst);
-                println!("New connection from {:?} ({}/{})", peer_addr, count, server.max_connections);
-                
-                // Clone for drop handler
-                let counter = server.connection_count.clone();
-                let max_connections = server.max_connections;
-                
-                // Execute the channel - note that server is cloned because serve() takes ownership
-                let fut = channel.execute(server.clone().serve());
-                
-                // Spawn task to handle this client
-                tokio::spawn(async move {
-                    // Process client requests
-                    fut.await;
-                    
-                    // Decrement counter when client disconnects
-                    let new_count = counter.fetch_sub(1, Ordering::SeqCst) - 1;
-                    println!("Client {:?} disconnected. Active connections: {}/{}",
-                             peer_addr, new_count, max_connections);
-                });
-            }
-        })
-        .await;
-    
-    Ok(())
+                Err(_) => {
+                    println!("Closing connection from {:?}: handshake timed out after {:?}",
+                             peer_addr, server.handshake_timeout);
+                    return;
+                }
+            };
+            println!("Peer {:?} authenticated as {:?}, negotiated codec {:?}", peer_addr, role, codec);
+
+            // Ask the admission manager whether there's room for this peer, evicting a
+            // lower-weight connection if necessary. This runs after authentication so an
+            // unauthenticated scanner can't burn a connection slot. Admission is keyed by a
+            // fresh connection id rather than `peer_addr.ip()`: a peer with more than one
+            // concurrent connection must not have one connection's bookkeeping clobber another's.
+            let connection_id = match server.admission.try_admit(peer_addr.ip()) {
+                Admission::Admitted(id) => {
+                    if let Err(e) = handshake::send_decision(&mut socket, codec, true, None).await {
+                        println!("Closing connection from {:?}: failed to ack handshake: {}", peer_addr, e);
+                        server.admission.remove(id);
+                        return;
+                    }
+                    println!("New connection from {:?} ({}/{})",
+                             peer_addr, server.admission.active_count(), server.max_connections);
+                    id
+                }
+                Admission::AdmittedEvicting(id, evicted_ip) => {
+                    if let Err(e) = handshake::send_decision(&mut socket, codec, true, None).await {
+                        println!("Closing connection from {:?}: failed to ack handshake: {}", peer_addr, e);
+                        server.admission.remove(id);
+                        return;
+                    }
+                    println!("New connection from {:?} evicted lower-weight peer {:?} ({:?}) ({}/{})",
+                             peer_addr, evicted_ip, CloseReason::Evicted,
+                             server.admission.active_count(), server.max_connections);
+                    id
+                }
+                Admission::Rejected => {
+                    println!("Connection from {:?} rejected: {:?}", peer_addr, CloseReason::Rejected);
+                    let _ = handshake::send_decision(&mut socket, codec, false, Some(CloseReason::Rejected.as_str().to_string())).await;
+                    return;
+                }
+            };
+
+            let admission = server.admission.clone();
+            let max_connections = server.max_connections;
+            let idle_timeout = server.idle_timeout;
+
+            // Wrap the socket per the negotiated codec, actually compressing the wire bytes for
+            // Codec::Compression rather than just labeling them.
+            let stream = handshake::wrap_negotiated(socket, codec);
+            let mut transport = tarpc::serde_transport::new(stream, Json::default());
+            transport.config_mut().max_frame_length(usize::MAX);
+            let channel = server::BaseChannel::with_defaults(transport);
+
+            let connection = Connection {
+                server: server.clone(),
+                connection_id,
+                peer_ip: peer_addr.ip(),
+                role,
+                last_activity: Arc::new(Mutex::new(Instant::now())),
+            };
+            let last_activity = connection.last_activity.clone();
+            let admitted_at = *last_activity.lock().unwrap();
+            let first_request_activity = last_activity.clone();
+            let handshake_timeout = server.handshake_timeout;
+
+            // Execute the channel - note that connection is cloned because serve() takes ownership
+            let fut = channel.execute(connection.clone().serve());
+
+            // Spawn task to handle this client
+            let task = tokio::spawn(async move {
+                tokio::select! {
+                    _ = fut => {}
+                    _ = wait_for_idle(last_activity, idle_timeout) => {
+                        println!("Closing idle connection from {:?} (no activity for {:?})",
+                                 peer_addr, idle_timeout);
+                    }
+                    _ = async {
+                        tokio::time::sleep(handshake_timeout).await;
+                        let made_request = *first_request_activity.lock().unwrap() != admitted_at;
+                        if made_request {
+                            future::pending::<()>().await;
+                        }
+                    } => {
+                        println!("Closing connection from {:?}: no first request within {:?}",
+                                 peer_addr, handshake_timeout);
+                    }
+                }
+
+                // Free this peer's slot now that its channel has stopped being served, whether
+                // that's because the client disconnected or the idle or first-request timeout fired.
+                admission.remove(connection_id);
+                println!("Client {:?} disconnected. Active connections: {}/{}",
+                         peer_addr, admission.active_count(), max_connections);
+            });
+            server.admission.register_handle(connection_id, Box::new(TaskCloseHandle { task, peer_addr }));
+        });
+    }
 } 
\ No newline at end of file
@@ -1,3 +1,5 @@
+use std::net::IpAddr;
+
 use serde::{Deserialize, Serialize};
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -21,12 +23,112 @@ pub struct DeleteRequest {
     pub key: String,
 }
 
+/// The RPC protocol version spoken by this build. Bump when a wire-incompatible change is made
+/// to a request/response type; `Capabilities` covers additive, optional features instead.
+pub const PROTOCOL_VERSION: u32 = 1;
+
+/// A set of optional protocol features advertised as a 64-bit flags value, so the crate can grow
+/// new RPCs over time without breaking peers that don't know about them yet: each side sends its
+/// own bitfield and masks its behavior down to the intersection.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Capabilities(pub u64);
+
+impl Capabilities {
+    pub const BATCH_OPERATIONS: u64 = 1 << 0;
+    pub const KEY_TTL: u64 = 1 << 1;
+    pub const COMPARE_AND_SWAP: u64 = 1 << 2;
+    pub const RANGE_SCANS: u64 = 1 << 3;
+
+    pub fn none() -> Self {
+        Capabilities(0)
+    }
+
+    pub fn with_batch_operations(mut self) -> Self {
+        self.0 |= Self::BATCH_OPERATIONS;
+        self
+    }
+
+    pub fn with_key_ttl(mut self) -> Self {
+        self.0 |= Self::KEY_TTL;
+        self
+    }
+
+    pub fn with_compare_and_swap(mut self) -> Self {
+        self.0 |= Self::COMPARE_AND_SWAP;
+        self
+    }
+
+    pub fn with_range_scans(mut self) -> Self {
+        self.0 |= Self::RANGE_SCANS;
+        self
+    }
+
+    /// Whether every capability set in `other` is also set in `self`.
+    pub fn includes(&self, other: Capabilities) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    /// The capabilities both `self` and `other` advertise; what's safe to actually use.
+    pub fn intersection(&self, other: Capabilities) -> Capabilities {
+        Capabilities(self.0 & other.0)
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct HelloRequest {
+    pub protocol_version: u32,
+    pub capabilities: Capabilities,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct HelloResponse {
+    pub protocol_version: u32,
+    pub capabilities: Capabilities,
+}
+
+/// Per-connection counters returned by the `admin_stats` RPC.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ConnectionStats {
+    pub ip: IpAddr,
+    pub set_calls: u64,
+    pub get_calls: u64,
+    pub delete_calls: u64,
+    pub bytes_processed: u64,
+    pub connected_secs: u64,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct AdminStatsResponse {
+    pub connections: Vec<ConnectionStats>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct DisconnectRequest {
+    pub ip: IpAddr,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct DisconnectResponse {
+    pub disconnected: bool,
+}
+
 #[tarpc::service]
 pub trait KeyValueStore {
+    /// Exchange protocol version and capability bitfields. The server replies with its own
+    /// version and the intersection of both sides' capabilities, so a client never calls an
+    /// RPC the server didn't also advertise.
+    async fn hello(req: HelloRequest) -> HelloResponse;
     /// Set a key-value pair
     async fn set(req: SetRequest) -> ();
     /// Get a value by key
     async fn get(req: GetRequest) -> GetResponse;
     /// Delete a key-value pair
     async fn delete(req: DeleteRequest) -> ();
-} 
\ No newline at end of file
+    /// Zero-payload heartbeat used by `ReconnectingClient` to detect a dead link while idle.
+    async fn ping() -> ();
+    /// Admin-only: a snapshot of every active connection and its call/byte counters.
+    async fn admin_stats() -> AdminStatsResponse;
+    /// Admin-only: forcibly drop every connection from the given IP.
+    async fn disconnect(req: DisconnectRequest) -> DisconnectResponse;
+}
+